@@ -0,0 +1,139 @@
+//! Types for the index node status API, kept free of any particular
+//! storage backend so that API code can depend on `Arc<dyn StatusStore>`
+//! instead of on a specific store implementation.
+use web3::types::H256;
+
+use crate::data::subgraph::schema::SubgraphHealth;
+use crate::prelude::StoreError;
+
+/// A block pointer as surfaced by the status API: a hash together with the
+/// block number it's at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EthereumBlock {
+    pub hash: H256,
+    pub number: u64,
+}
+
+impl EthereumBlock {
+    pub fn new(hash: H256, number: u64) -> Self {
+        Self { hash, number }
+    }
+}
+
+/// How far a deployment has fallen behind the chain head.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LagStatus {
+    InSync,
+    Lagging,
+    Stalled,
+}
+
+/// Per-chain indexing progress for a deployment.
+#[derive(Debug)]
+pub struct ChainInfo {
+    pub network: String,
+    pub chain_head_block: Option<EthereumBlock>,
+    pub earliest_block: Option<EthereumBlock>,
+    pub latest_block: Option<EthereumBlock>,
+    pub lag: Option<u64>,
+    pub lag_status: LagStatus,
+    pub last_reorg: Option<EthereumBlock>,
+    pub reorg_count: u64,
+}
+
+#[derive(Debug)]
+pub struct SubgraphError {
+    pub subgraph_id: String,
+    pub message: String,
+    pub block: Option<EthereumBlock>,
+    pub handler: Option<String>,
+    pub deterministic: bool,
+}
+
+/// The indexing status of a single deployment, as returned by the index
+/// node status API.
+#[derive(Debug)]
+pub struct Info {
+    pub subgraph: String,
+    pub synced: bool,
+    pub health: SubgraphHealth,
+    pub fatal_error: Option<SubgraphError>,
+    pub non_fatal_errors: Vec<SubgraphError>,
+    pub chains: Vec<ChainInfo>,
+    pub node: Option<String>,
+}
+
+/// Thresholds, in number of blocks, used to classify how far a deployment
+/// has fallen behind the chain head.
+#[derive(Copy, Clone, Debug)]
+pub struct LagThresholds {
+    /// Lag, in blocks, at and above which a deployment is `Lagging`.
+    pub lagging_at: u64,
+    /// Lag, in blocks, at and above which a deployment is `Stalled`.
+    pub stalled_at: u64,
+}
+
+impl Default for LagThresholds {
+    fn default() -> Self {
+        Self {
+            lagging_at: 50,
+            stalled_at: 1_000,
+        }
+    }
+}
+
+impl LagThresholds {
+    pub fn classify(&self, lag: Option<u64>) -> LagStatus {
+        match lag {
+            Some(lag) if lag >= self.stalled_at => LagStatus::Stalled,
+            Some(lag) if lag >= self.lagging_at => LagStatus::Lagging,
+            _ => LagStatus::InSync,
+        }
+    }
+}
+
+/// Optional filter for `StatusStore::deployment_statuses` so that callers
+/// who only care about unhealthy deployments don't have to load every row
+/// just to throw most of them away. When both `min_lag` and `failed_only`
+/// are set, a deployment is returned if it meets either condition.
+#[derive(Clone, Debug, Default)]
+pub struct StatusFilter {
+    /// Return deployments whose indexing lag is strictly greater than this
+    /// many blocks.
+    pub min_lag: Option<u64>,
+    /// Return deployments whose health is `failed`.
+    pub failed_only: bool,
+}
+
+/// A page of a keyset-paginated query, together with the cursor to pass as
+/// `after_vid` to fetch the next page. `next_vid` is `None` once fewer than
+/// `limit` rows come back, i.e. the caller has reached the end.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_vid: Option<i64>,
+}
+
+/// Backend-agnostic access to the data backing the index node status API.
+/// The index node API should depend on `Arc<dyn StatusStore>` rather than
+/// on a particular store implementation.
+pub trait StatusStore: Send + Sync + 'static {
+    fn deployment_statuses(
+        &self,
+        deployments: Vec<String>,
+        filter: Option<StatusFilter>,
+        lag_thresholds: LagThresholds,
+        after_vid: Option<i64>,
+        limit: usize,
+    ) -> Result<Page<Info>, StoreError>;
+
+    fn deployments_for_subgraph(
+        &self,
+        name: String,
+        after_vid: Option<i64>,
+        limit: usize,
+    ) -> Result<Page<String>, StoreError>;
+
+    fn subgraph_version(&self, name: String, use_current: bool)
+        -> Result<Option<String>, StoreError>;
+}
@@ -0,0 +1,49 @@
+//! Diesel table mappings for the `subgraphs` metadata tables. These are the
+//! canonical schema declarations; other modules that need to read or write
+//! these tables should import them from here rather than redeclaring them.
+table! {
+    subgraphs.subgraph (id) {
+        id -> Text,
+        name -> Text,
+        current_version -> Nullable<Text>,
+        pending_version -> Nullable<Text>,
+    }
+}
+
+table! {
+    subgraphs.subgraph_version (id) {
+        id -> Text,
+        subgraph -> Text,
+        deployment -> Text,
+        vid -> BigInt,
+    }
+}
+
+table! {
+    subgraphs.subgraph_deployment (id) {
+        id -> Text,
+        manifest -> Text,
+        failed -> Bool,
+        health -> Text,
+        synced -> Bool,
+        fatal_error -> Nullable<Text>,
+        non_fatal_errors -> Array<Text>,
+        earliest_ethereum_block_hash -> Nullable<Binary>,
+        earliest_ethereum_block_number -> Nullable<Numeric>,
+        latest_ethereum_block_hash -> Nullable<Binary>,
+        latest_ethereum_block_number -> Nullable<Numeric>,
+        entity_count -> Numeric,
+        graft_base -> Nullable<Text>,
+        graft_block_hash -> Nullable<Binary>,
+        graft_block_number -> Nullable<Numeric>,
+        ethereum_head_block_hash -> Nullable<Binary>,
+        ethereum_head_block_number -> Nullable<Numeric>,
+        network -> Text,
+        node_id -> Nullable<Text>,
+        reorg_count -> BigInt,
+        last_reorg_block_hash -> Nullable<Binary>,
+        last_reorg_block_number -> Nullable<Numeric>,
+        // We don't map block_range
+        // block_range -> Range<Integer>,
+    }
+}